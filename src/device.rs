@@ -1,20 +1,36 @@
 use hal::{self, Device as _Device};
 use hal::queue::RawCommandQueue;
-use {binding_model, command, conv, memory, pipeline, resource};
+use {binding_model, command, conv, memory, pipeline, query, resource};
 
-use std::{iter, slice};
+use std::{iter, mem, os::raw::c_void, ptr, slice};
 use registry::{self, Items, Registry};
 use {
-    AttachmentStateId, BindGroupLayoutId, BlendStateId, BufferId, CommandBufferId, DepthStencilStateId, DeviceId,
-    PipelineLayoutId, QueueId, RenderPipelineId, ShaderModuleId,
+    AttachmentStateId, BindGroupId, BindGroupLayoutId, BlendStateId, BufferId, CommandBufferId,
+    ComputePipelineId, DepthStencilStateId, DeviceId, FenceId, PipelineLayoutId, QuerySetId, QueueId,
+    RenderPipelineId, SemaphoreId, ShaderModuleId,
 };
 
 
+// Number of sets a freshly allocated descriptor pool block can hold before
+// `wgpu_device_create_bind_group` grows the device's pool list again.
+const DESC_POOL_BLOCK_SETS: usize = 64;
+
 pub struct Device<B: hal::Backend> {
     device: B::Device,
     queue_group: hal::QueueGroup<B, hal::General>,
     mem_allocator: memory::SmartAllocator<B>,
     com_allocator: command::CommandAllocator<B>,
+    // Descriptor pools backing `wgpu_device_create_bind_group`, grown one
+    // block at a time as existing pools run out of sets.
+    desc_pools: Vec<B::DescriptorPool>,
+    // Command buffers from submissions that signal a caller-owned fence,
+    // held here until a later `wgpu_queue_submit` observes that fence
+    // signaled, or a buffer map call waits on it, and retires them back to
+    // `com_allocator`.
+    pending_submissions: Vec<(FenceId, Vec<command::CommandBuffer<B>>)>,
+    // Limits reported by the adapter this device was created from, backing
+    // capability queries like `wgpu_device_get_max_view_count`.
+    limits: hal::Limits,
 }
 
 impl<B: hal::Backend> Device<B> {
@@ -22,12 +38,16 @@ impl<B: hal::Backend> Device<B> {
         device: B::Device,
         queue_group: hal::QueueGroup<B, hal::General>,
         mem_props: hal::MemoryProperties,
+        limits: hal::Limits,
     ) -> Self {
         Device {
             device,
             mem_allocator: memory::SmartAllocator::new(mem_props, 1, 1, 1, 1),
             com_allocator: command::CommandAllocator::new(queue_group.family()),
             queue_group,
+            desc_pools: Vec::new(),
+            pending_submissions: Vec::new(),
+            limits,
         }
     }
 }
@@ -36,6 +56,25 @@ pub(crate) struct ShaderModule<B: hal::Backend> {
     pub raw: B::ShaderModule,
 }
 
+pub(crate) struct Buffer<B: hal::Backend> {
+    pub raw: B::Buffer,
+    pub device_id: DeviceId,
+    pub memory_block: memory::Block<B>,
+    pub size: u64,
+    pub host_visible: bool,
+    // Whether `memory_block` is currently mapped, so `wgpu_buffer_unmap` only
+    // ever unmaps a buffer that a map_*_async call actually left mapped.
+    pub mapped: bool,
+}
+
+pub(crate) struct Fence<B: hal::Backend> {
+    pub raw: B::Fence,
+}
+
+pub(crate) struct Semaphore<B: hal::Backend> {
+    pub raw: B::Semaphore,
+}
+
 #[no_mangle]
 pub extern "C" fn wgpu_device_create_bind_group_layout(
     device_id: DeviceId,
@@ -124,6 +163,204 @@ pub extern "C" fn wgpu_device_create_command_buffer(
     registry::COMMAND_BUFFER_REGISTRY.register(cmd_buf)
 }
 
+#[no_mangle]
+pub extern "C" fn wgpu_device_create_buffer(
+    device_id: DeviceId,
+    desc: resource::BufferDescriptor,
+) -> BufferId {
+    let mut device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = device_guard.get_mut(device_id);
+
+    let usage = conv::map_buffer_usage(desc.usage);
+    let unbound = device.device.create_buffer(desc.size, usage).unwrap();
+    let requirements = device.device.get_buffer_requirements(&unbound);
+
+    let host_visible = desc.usage.contains(resource::BufferUsageFlags::MAP_READ)
+        || desc.usage.contains(resource::BufferUsageFlags::MAP_WRITE);
+    let memory_properties = if host_visible {
+        hal::memory::Properties::CPU_VISIBLE
+    } else {
+        hal::memory::Properties::DEVICE_LOCAL
+    };
+    let memory_block = device
+        .mem_allocator
+        .allocate(&device.device, requirements, memory_properties)
+        .unwrap();
+    let raw = device
+        .device
+        .bind_buffer_memory(memory_block.memory(), memory_block.range().start, unbound)
+        .unwrap();
+
+    registry::BUFFER_REGISTRY.register(Buffer {
+        raw,
+        device_id,
+        memory_block,
+        size: desc.size,
+        host_visible,
+        mapped: false,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_buffer_map_read_async(
+    buffer_id: BufferId,
+    start: u64,
+    size: u64,
+    callback: resource::BufferMapReadCallback,
+    userdata: *mut c_void,
+) {
+    let mut buffer_guard = registry::BUFFER_REGISTRY.lock();
+    let buffer = buffer_guard.get_mut(buffer_id);
+    let mut device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = device_guard.get_mut(buffer.device_id);
+
+    // Wait on the fence of every pending submission, since any of them may
+    // still be writing this buffer.
+    wait_for_pending_submissions(device);
+
+    if buffer.host_visible {
+        let offset = buffer.memory_block.range().start + start;
+        let mapped = unsafe {
+            device
+                .device
+                .map_memory(buffer.memory_block.memory(), offset..offset + size)
+                .unwrap()
+        };
+        callback(mapped.as_ptr(), size as usize, userdata);
+        buffer.mapped = true;
+    } else {
+        // Not CPU-visible: bounce through a host-visible staging buffer.
+        let device_id = buffer.device_id;
+        drop(device_guard);
+        drop(buffer_guard);
+        let staging_id = wgpu_device_create_buffer(
+            device_id,
+            resource::BufferDescriptor {
+                size,
+                usage: resource::BufferUsageFlags::TRANSFER_DST | resource::BufferUsageFlags::MAP_READ,
+            },
+        );
+        copy_buffer_to_buffer(device_id, buffer_id, start, staging_id, 0, size);
+        wgpu_buffer_map_read_async(staging_id, 0, size, callback, userdata);
+        wgpu_buffer_unmap(staging_id);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_buffer_map_write_async(
+    buffer_id: BufferId,
+    start: u64,
+    size: u64,
+    callback: resource::BufferMapWriteCallback,
+    userdata: *mut c_void,
+) {
+    let mut buffer_guard = registry::BUFFER_REGISTRY.lock();
+    let buffer = buffer_guard.get_mut(buffer_id);
+    let mut device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = device_guard.get_mut(buffer.device_id);
+
+    wait_for_pending_submissions(device);
+
+    if buffer.host_visible {
+        let offset = buffer.memory_block.range().start + start;
+        let mapped = unsafe {
+            device
+                .device
+                .map_memory(buffer.memory_block.memory(), offset..offset + size)
+                .unwrap()
+        };
+        callback(mapped.as_mut_ptr(), size as usize, userdata);
+        buffer.mapped = true;
+    } else {
+        // Not CPU-visible: write into a staging buffer, then copy it down
+        // onto the real one once the callback is done filling it in.
+        let device_id = buffer.device_id;
+        drop(device_guard);
+        drop(buffer_guard);
+        let staging_id = wgpu_device_create_buffer(
+            device_id,
+            resource::BufferDescriptor {
+                size,
+                usage: resource::BufferUsageFlags::TRANSFER_SRC | resource::BufferUsageFlags::MAP_WRITE,
+            },
+        );
+        wgpu_buffer_map_write_async(staging_id, 0, size, callback, userdata);
+        wgpu_buffer_unmap(staging_id);
+        copy_buffer_to_buffer(device_id, staging_id, 0, buffer_id, start, size);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_buffer_unmap(buffer_id: BufferId) {
+    let mut buffer_guard = registry::BUFFER_REGISTRY.lock();
+    let buffer = buffer_guard.get_mut(buffer_id);
+    if buffer.mapped {
+        let device_guard = registry::DEVICE_REGISTRY.lock();
+        let device = &device_guard.get(buffer.device_id).device;
+        device.unmap_memory(buffer.memory_block.memory());
+        buffer.mapped = false;
+    }
+}
+
+// Waits on the fences of submissions not yet known complete, retiring their
+// command buffers back to `com_allocator`. Shared by the buffer mapping
+// calls, which need every outstanding write observed before handing the
+// caller a pointer into the buffer's memory.
+fn wait_for_pending_submissions<B: hal::Backend>(device: &mut Device<B>) {
+    let fence_guard = registry::FENCE_REGISTRY.lock();
+    let pending = mem::replace(&mut device.pending_submissions, Vec::new());
+    for (fence_id, cmd_bufs) in pending {
+        device
+            .device
+            .wait_for_fence(&fence_guard.get(fence_id).raw, !0)
+            .unwrap();
+        for cmd_buf in cmd_bufs {
+            device.com_allocator.submit(cmd_buf);
+        }
+    }
+}
+
+fn copy_buffer_to_buffer(
+    device_id: DeviceId,
+    src_id: BufferId,
+    src_offset: u64,
+    dst_id: BufferId,
+    dst_offset: u64,
+    size: u64,
+) {
+    let buffer_guard = registry::BUFFER_REGISTRY.lock();
+    let mut device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = device_guard.get_mut(device_id);
+
+    let mut cmd_buf = device.com_allocator.allocate(&device.device);
+    unsafe {
+        cmd_buf.raw.begin(false);
+        cmd_buf.raw.copy_buffer(
+            &buffer_guard.get(src_id).raw,
+            &buffer_guard.get(dst_id).raw,
+            iter::once(hal::command::BufferCopy {
+                src: src_offset,
+                dst: dst_offset,
+                size,
+            }),
+        );
+        cmd_buf.raw.finish();
+    }
+
+    let submission = hal::queue::RawSubmission {
+        cmd_buffers: iter::once(&cmd_buf.raw),
+        wait_semaphores: &[],
+        signal_semaphores: &[],
+    };
+    unsafe {
+        device.queue_group.queues[0]
+            .as_raw_mut()
+            .submit_raw(submission, None);
+    }
+    device.device.wait_idle().unwrap();
+    device.com_allocator.submit(cmd_buf);
+}
+
 #[no_mangle]
 pub extern "C" fn wgpu_device_get_queue(
     device_id: DeviceId,
@@ -131,55 +368,163 @@ pub extern "C" fn wgpu_device_get_queue(
    device_id
 }
 
+#[no_mangle]
+pub extern "C" fn wgpu_device_get_max_view_count(device_id: DeviceId) -> u32 {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    device_guard.get(device_id).limits.max_view_count as u32
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_device_create_fence(device_id: DeviceId, signaled: bool) -> FenceId {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = &device_guard.get(device_id).device;
+    let raw = device.create_fence(signaled).unwrap();
+    registry::FENCE_REGISTRY.register(Fence { raw })
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_device_create_semaphore(device_id: DeviceId) -> SemaphoreId {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = &device_guard.get(device_id).device;
+    let raw = device.create_semaphore().unwrap();
+    registry::SEMAPHORE_REGISTRY.register(Semaphore { raw })
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_device_wait_fence(device_id: DeviceId, fence_id: FenceId, timeout_ns: u64) -> bool {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = &device_guard.get(device_id).device;
+    let fence_guard = registry::FENCE_REGISTRY.lock();
+    let fence = fence_guard.get(fence_id);
+    device.wait_for_fence(&fence.raw, timeout_ns).unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_fence_get_completed_value(device_id: DeviceId, fence_id: FenceId) -> bool {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = &device_guard.get(device_id).device;
+    let fence_guard = registry::FENCE_REGISTRY.lock();
+    let fence = fence_guard.get(fence_id);
+    device.get_fence_status(&fence.raw).unwrap_or(false)
+}
+
 #[no_mangle]
 pub extern "C" fn wgpu_queue_submit(
     queue_id: QueueId,
     command_buffer_ptr: *const CommandBufferId,
     command_buffer_count: usize,
+    wait_semaphore_ptr: *const SemaphoreId,
+    wait_semaphore_count: usize,
+    signal_semaphore_ptr: *const SemaphoreId,
+    signal_semaphore_count: usize,
+    fence_id: *const FenceId,
 ) {
     let mut device = registry::DEVICE_REGISTRY.get_mut(queue_id);
     let command_buffer_ids = unsafe {
         slice::from_raw_parts(command_buffer_ptr, command_buffer_count)
     };
+    let wait_semaphore_ids = unsafe {
+        slice::from_raw_parts(wait_semaphore_ptr, wait_semaphore_count)
+    };
+    let signal_semaphore_ids = unsafe {
+        slice::from_raw_parts(signal_semaphore_ptr, signal_semaphore_count)
+    };
+
+    let semaphore_guard = registry::SEMAPHORE_REGISTRY.lock();
+    let wait_semaphores = wait_semaphore_ids
+        .iter()
+        .map(|&id| (&semaphore_guard.get(id).raw, hal::pso::PipelineStage::TOP_OF_PIPE))
+        .collect::<Vec<_>>();
+    let signal_semaphores = signal_semaphore_ids
+        .iter()
+        .map(|&id| &semaphore_guard.get(id).raw)
+        .collect::<Vec<_>>();
+
+    // Following the halmark execution-context pattern: before looking at this
+    // submission, reap command buffers from earlier ones whose fence has
+    // since signaled, rather than retiring them up front.
+    {
+        let fence_guard = registry::FENCE_REGISTRY.lock();
+        let pending = mem::replace(&mut device.pending_submissions, Vec::new());
+        for (pending_fence_id, pending_cmd_bufs) in pending {
+            let signaled = device
+                .device
+                .get_fence_status(&fence_guard.get(pending_fence_id).raw)
+                .unwrap_or(false);
+            if signaled {
+                for cmd_buf in pending_cmd_bufs {
+                    device.com_allocator.submit(cmd_buf);
+                }
+            } else {
+                device.pending_submissions.push((pending_fence_id, pending_cmd_bufs));
+            }
+        }
+    }
+
+    let fence_guard = registry::FENCE_REGISTRY.lock();
+
     //TODO: submit at once, requires `get_all()`
-    for &cmb_id in command_buffer_ids {
-        let cmd_buf = registry::COMMAND_BUFFER_REGISTRY.take(cmb_id);
-        {
-            let submission = hal::queue::RawSubmission {
-                cmd_buffers: iter::once(&cmd_buf.raw),
-                wait_semaphores: &[],
-                signal_semaphores: &[],
-            };
+    let cmd_bufs = command_buffer_ids
+        .iter()
+        .map(|&id| registry::COMMAND_BUFFER_REGISTRY.take(id))
+        .collect::<Vec<_>>();
+
+    let submission = hal::queue::RawSubmission {
+        cmd_buffers: cmd_bufs.iter().map(|cmd_buf| &cmd_buf.raw),
+        wait_semaphores: &wait_semaphores,
+        signal_semaphores: &signal_semaphores,
+    };
+
+    match unsafe { fence_id.as_ref() } {
+        Some(&id) => {
+            // The caller owns this fence and is responsible for polling it
+            // via `wgpu_device_wait_fence`/`wgpu_fence_get_completed_value`;
+            // don't block here. Retire these command buffers once a later
+            // submit observes the fence signaled.
             unsafe {
                 device.queue_group.queues[0]
                     .as_raw_mut()
-                    .submit_raw(submission, None);
+                    .submit_raw(submission, Some(&fence_guard.get(id).raw));
+            }
+            device.pending_submissions.push((id, cmd_bufs));
+        }
+        None => {
+            let internal_fence = device.device.create_fence(false).unwrap();
+            unsafe {
+                device.queue_group.queues[0]
+                    .as_raw_mut()
+                    .submit_raw(submission, Some(&internal_fence));
+            }
+            device.device.wait_for_fence(&internal_fence, !0).unwrap();
+            device.device.destroy_fence(internal_fence);
+            for cmd_buf in cmd_bufs {
+                device.com_allocator.submit(cmd_buf);
             }
         }
-        device.com_allocator.submit(cmd_buf);
     }
+}
+
+#[no_mangle]
 pub extern "C" fn wgpu_device_create_attachment_state(
     device_id: DeviceId,
     desc: pipeline::AttachmentStateDescriptor,
 ) -> AttachmentStateId {
-    // TODO: Assume that `AttachmentStateDescriptor` contains multiple attachments.
-    let attachments = unsafe { slice::from_raw_parts(desc.formats, desc.formats_length) }
+    let attachments = unsafe { slice::from_raw_parts(desc.attachments, desc.attachments_length) }
         .iter()
-        .map(|format| {
+        .map(|attachment| {
             hal::pass::Attachment {
-                format: Some(conv::map_texture_format(*format)),
-                samples: 1, // TODO map
+                format: Some(conv::map_texture_format(attachment.format)),
+                samples: attachment.samples as u8,
                 ops: hal::pass::AttachmentOps {
-                    // TODO map
-                    load: hal::pass::AttachmentLoadOp::Clear,
-                    store: hal::pass::AttachmentStoreOp::Store,
+                    load: conv::map_load_op(attachment.load_op),
+                    store: conv::map_store_op(attachment.store_op),
                 },
                 stencil_ops: hal::pass::AttachmentOps {
-                    // TODO map
-                    load: hal::pass::AttachmentLoadOp::DontCare,
-                    store: hal::pass::AttachmentStoreOp::DontCare,
+                    load: conv::map_load_op(attachment.stencil_load_op),
+                    store: conv::map_store_op(attachment.stencil_store_op),
                 },
-                layouts: hal::image::Layout::Undefined..hal::image::Layout::Present, // TODO map
+                layouts: conv::map_texture_layout(attachment.begin_layout)
+                    ..conv::map_texture_layout(attachment.end_layout),
             }
         }).collect();
     registry::ATTACHMENT_STATE_REGISTRY.register(pipeline::AttachmentState { raw: attachments })
@@ -252,11 +597,27 @@ pub extern "C" fn wgpu_device_create_render_pipeline(
         conservative: false,
     };
 
-    // TODO
-    let vertex_buffers: Vec<hal::pso::VertexBufferDesc> = Vec::new();
-
-    // TODO
-    let attributes: Vec<hal::pso::AttributeDesc> = Vec::new();
+    let vertex_buffers = unsafe { slice::from_raw_parts(desc.vertex_buffers, desc.vertex_buffers_length) }
+        .iter()
+        .enumerate()
+        .map(|(i, vb)| hal::pso::VertexBufferDesc {
+            binding: i as u32,
+            stride: vb.stride,
+            rate: conv::map_input_step_mode(vb.step_mode),
+        })
+        .collect::<Vec<_>>();
+
+    let attributes = unsafe { slice::from_raw_parts(desc.attributes, desc.attributes_length) }
+        .iter()
+        .map(|attribute| hal::pso::AttributeDesc {
+            location: attribute.shader_location,
+            binding: attribute.input_slot,
+            element: hal::pso::Element {
+                format: conv::map_vertex_format(attribute.format),
+                offset: attribute.offset,
+            },
+        })
+        .collect::<Vec<_>>();
 
     let input_assembler = hal::pso::InputAssemblerDesc {
         primitive: conv::map_primitive_topology(desc.primitive_topology),
@@ -311,6 +672,9 @@ pub extern "C" fn wgpu_device_create_render_pipeline(
         inputs: &[],
         resolves: &[],
         preserves: &[],
+        // Low bits select which views (array layers) this subpass is
+        // broadcast to in a single draw, e.g. one bit per stereo eye.
+        view_mask: desc.view_mask,
     };
 
     // TODO
@@ -359,4 +723,207 @@ pub extern "C" fn wgpu_device_create_render_pipeline(
         .unwrap();
 
     registry::RENDER_PIPELINE_REGISTRY.register(pipeline::RenderPipeline { raw: pipeline })
-}
\ No newline at end of file
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_device_create_compute_pipeline(
+    device_id: DeviceId,
+    desc: pipeline::ComputePipelineDescriptor,
+) -> ComputePipelineId {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = &device_guard.get(device_id).device;
+
+    let pipeline_layout_guard = registry::PIPELINE_LAYOUT_REGISTRY.lock();
+    let layout = &pipeline_layout_guard.get(desc.layout).raw;
+
+    let shader_module_guard = registry::SHADER_MODULE_REGISTRY.lock();
+    let entry = unsafe { ffi::CStr::from_ptr(desc.stage.entry_point) }
+        .to_str()
+        .to_owned()
+        .unwrap();
+    let shader = hal::pso::EntryPoint::<back::Backend> {
+        entry,
+        module: &shader_module_guard.get(desc.stage.module).raw,
+        specialization: hal::pso::Specialization {
+            // TODO
+            constants: &[],
+            data: &[],
+        },
+    };
+
+    // TODO
+    let flags = hal::pso::PipelineCreationFlags::empty();
+
+    // TODO
+    let parent = hal::pso::BasePipeline::None;
+
+    let pipeline_desc = hal::pso::ComputePipelineDesc {
+        shader,
+        layout,
+        flags,
+        parent,
+    };
+
+    // TODO: cache
+    let pipeline = device
+        .create_compute_pipeline(&pipeline_desc, None)
+        .unwrap();
+
+    registry::COMPUTE_PIPELINE_REGISTRY.register(pipeline::ComputePipeline { raw: pipeline })
+}
+#[no_mangle]
+pub extern "C" fn wgpu_device_create_query_set(
+    device_id: DeviceId,
+    desc: query::QuerySetDescriptor,
+) -> QuerySetId {
+    let device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = &device_guard.get(device_id).device;
+    let raw = device
+        .create_query_pool(conv::map_query_type(desc.ty), desc.count)
+        .unwrap();
+    registry::QUERY_SET_REGISTRY.register(query::QuerySet {
+        raw,
+        ty: desc.ty,
+        count: desc.count,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_command_buffer_begin_occlusion_query(
+    command_buffer_id: CommandBufferId,
+    query_set_id: QuerySetId,
+    query_index: u32,
+) {
+    let mut cmd_buf_guard = registry::COMMAND_BUFFER_REGISTRY.lock();
+    let cmd_buf = cmd_buf_guard.get_mut(command_buffer_id);
+    let query_set_guard = registry::QUERY_SET_REGISTRY.lock();
+    let query_set = query_set_guard.get(query_set_id);
+    unsafe {
+        cmd_buf.raw.begin_query(
+            hal::query::Query {
+                pool: &query_set.raw,
+                id: query_index,
+            },
+            hal::query::ControlFlags::empty(),
+        );
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_command_buffer_end_occlusion_query(
+    command_buffer_id: CommandBufferId,
+    query_set_id: QuerySetId,
+    query_index: u32,
+) {
+    let mut cmd_buf_guard = registry::COMMAND_BUFFER_REGISTRY.lock();
+    let cmd_buf = cmd_buf_guard.get_mut(command_buffer_id);
+    let query_set_guard = registry::QUERY_SET_REGISTRY.lock();
+    let query_set = query_set_guard.get(query_set_id);
+    unsafe {
+        cmd_buf.raw.end_query(hal::query::Query {
+            pool: &query_set.raw,
+            id: query_index,
+        });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_queue_resolve_query_set(
+    queue_id: QueueId,
+    query_set_id: QuerySetId,
+    first_query: u32,
+    query_count: u32,
+    destination_buffer_id: BufferId,
+    destination_offset: u64,
+) {
+    // Lock BUFFER_REGISTRY before DEVICE_REGISTRY, matching
+    // `copy_buffer_to_buffer`'s order, to avoid a lock-order inversion
+    // between the two.
+    let buffer_guard = registry::BUFFER_REGISTRY.lock();
+    let destination = buffer_guard.get(destination_buffer_id);
+    let query_set_guard = registry::QUERY_SET_REGISTRY.lock();
+    let query_set = query_set_guard.get(query_set_id);
+
+    let mut device = registry::DEVICE_REGISTRY.get_mut(queue_id);
+    let mut cmd_buf = device.com_allocator.allocate(&device.device);
+    unsafe {
+        cmd_buf.raw.begin(false);
+        cmd_buf.raw.copy_query_pool_results(
+            &query_set.raw,
+            first_query..first_query + query_count,
+            &destination.raw,
+            destination_offset,
+            8, // stride: one u64 result per query
+            hal::query::ResultFlags::WAIT | hal::query::ResultFlags::BITS_64,
+        );
+        cmd_buf.raw.finish();
+    }
+    drop(device);
+    // Drop before registering the command buffer: `COMMAND_BUFFER_REGISTRY`
+    // must be locked without `QUERY_SET_REGISTRY`/`BUFFER_REGISTRY` held, to
+    // match the order `wgpu_command_buffer_begin/end_occlusion_query` use
+    // (`COMMAND_BUFFER_REGISTRY` then `QUERY_SET_REGISTRY`) and avoid an
+    // ABBA deadlock between the two.
+    drop(query_set_guard);
+    drop(buffer_guard);
+    let cmd_buf_id = registry::COMMAND_BUFFER_REGISTRY.register(cmd_buf);
+
+    // Reuse the fence-aware submission path of `wgpu_queue_submit`, which
+    // waits on a fence for this submission and retires the command buffer,
+    // instead of the device-wide `wait_idle` that `copy_buffer_to_buffer` uses.
+    wgpu_queue_submit(queue_id, &cmd_buf_id, 1, ptr::null(), 0, ptr::null(), 0, ptr::null());
+}
+
+#[no_mangle]
+pub extern "C" fn wgpu_device_create_bind_group(
+    device_id: DeviceId,
+    desc: binding_model::BindGroupDescriptor,
+) -> BindGroupId {
+    let bindings = unsafe { slice::from_raw_parts(desc.bindings, desc.bindings_length) };
+
+    // Lock BIND_GROUP_LAYOUT_REGISTRY before DEVICE_REGISTRY, matching
+    // `wgpu_device_create_pipeline_layout`'s order, to avoid an ABBA
+    // deadlock between the two entry points.
+    let bind_group_layout_guard = registry::BIND_GROUP_LAYOUT_REGISTRY.lock();
+    let layout = &bind_group_layout_guard.get(desc.layout).raw;
+
+    let mut device_guard = registry::DEVICE_REGISTRY.lock();
+    let device = device_guard.get_mut(device_id);
+
+    let ranges = bindings
+        .iter()
+        .map(|binding| hal::pso::DescriptorRangeDesc {
+            ty: conv::map_binding_type(&binding.ty),
+            count: DESC_POOL_BLOCK_SETS,
+        })
+        .collect::<Vec<_>>();
+
+    let set = match device
+        .desc_pools
+        .last_mut()
+        .and_then(|pool| pool.allocate_set(layout).ok())
+    {
+        Some(set) => set,
+        None => {
+            // The newest block can't hold this set (or there isn't one yet):
+            // grow by allocating a fresh block and retrying from it. Older
+            // blocks are not tried again.
+            let mut pool = device
+                .device
+                .create_descriptor_pool(DESC_POOL_BLOCK_SETS, &ranges);
+            let set = pool.allocate_set(layout).unwrap();
+            device.desc_pools.push(pool);
+            set
+        }
+    };
+
+    let writes = bindings.iter().map(|binding| hal::pso::DescriptorSetWrite {
+        set: &set,
+        binding: binding.binding,
+        array_offset: 0,
+        descriptors: iter::once(conv::map_binding_resource(&binding.resource)),
+    });
+    device.device.write_descriptor_sets(writes);
+
+    registry::BIND_GROUP_REGISTRY.register(binding_model::BindGroup { raw: set })
+}